@@ -28,68 +28,128 @@ macro_rules! ensure {
     };
 }
 
+mod v5;
+
+/// Decodes one packet, given its already-parsed fixed header, from a cursor positioned right
+/// after that header.
+///
+/// `src` may hold more than just this packet (e.g. the rest of a streaming socket buffer), so
+/// this first checks that `header.remaining_length` bytes are actually available; if not, it
+/// returns `Ok(None)` and leaves `src`'s position untouched so the caller can retry once more
+/// bytes have arrived. Once that much data is confirmed present, decoding works against a cursor
+/// bounded to exactly the packet body, so any further length mismatch (e.g. a string prefix
+/// that doesn't fit) is a genuine protocol violation rather than a buffering issue.
 pub(crate) fn read_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
-) -> Result<Packet, ParseError> {
+    version: ProtocolVersion,
+) -> Result<Option<Packet>, ParseError> {
+    if src.remaining() < header.remaining_length {
+        return Ok(None);
+    }
+
+    let pos = src.position() as usize;
+    let body = src.get_ref().slice(pos, pos + header.remaining_length);
+    src.advance(header.remaining_length);
+
+    let mut body = Cursor::new(body);
+    let packet = match version {
+        ProtocolVersion::MQTT311 => read_packet_v311(&mut body, header),
+        ProtocolVersion::MQTT5 => v5::read_packet_v5(&mut body, header),
+    }?;
+    Ok(Some(packet))
+}
+
+fn read_packet_v311(src: &mut Cursor<Bytes>, header: FixedHeader) -> Result<Packet, ParseError> {
     match header.packet_type {
         CONNECT => decode_connect_packet(src, header),
         CONNACK => decode_connect_ack_packet(src, header),
-        PUBLISH => decode_publish_packet(src, header),
+        PUBLISH => decode_publish_packet(src, header, ProtocolVersion::MQTT311),
         PUBACK => decode_publish_ack_packet(src, header),
         PUBREC => decode_publish_rec_packet(src, header),
         PUBREL => decode_publish_rel_packet(src, header),
         PUBCOMP => decode_publish_comp_packet(src, header),
-        SUBSCRIBE => decode_subscribe_packet(src, header),
+        SUBSCRIBE => decode_subscribe_packet(src, header, ProtocolVersion::MQTT311),
         SUBACK => decode_subscribe_ack_packet(src, header),
-        UNSUBSCRIBE => decode_unsubscribe_packet(src, header),
+        UNSUBSCRIBE => decode_unsubscribe_packet(src, header, ProtocolVersion::MQTT311),
         UNSUBACK => decode_unsubscribe_ack_packet(src, header),
         PINGREQ => {
-            ensure!(
-                header.packet_flags.trailing_zeros() >= 4,
-                ParseError::FixedHeaderReservedFlagsMismatch
-            );
+            ensure_reserved_flags(header.packet_flags, 0b0000)?;
             Ok(Packet::PingRequest)
         }
         PINGRESP => {
-            ensure!(
-                header.packet_flags.trailing_zeros() >= 4,
-                ParseError::FixedHeaderReservedFlagsMismatch
-            );
+            ensure_reserved_flags(header.packet_flags, 0b0000)?;
             Ok(Packet::PingResponse)
         }
         DISCONNECT => {
-            ensure!(
-                header.packet_flags.trailing_zeros() >= 4,
-                ParseError::FixedHeaderReservedFlagsMismatch
-            );
+            ensure_reserved_flags(header.packet_flags, 0b0000)?;
             Ok(Packet::Disconnect)
         }
         _ => Err(ParseError::UnsupportedPacketType),
     }
 }
 
+/// Checks a fixed header's reserved flag bits against the exact pattern the spec mandates for
+/// this packet type (e.g. `0b0000` for PUBACK, `0b0010` for PUBREL), rather than the looser
+/// "high nibble only" check that conflates "matches" with "merely not using the high bits".
+fn ensure_reserved_flags(flags: u8, expected: u8) -> Result<(), ParseError> {
+    ensure!(flags == expected, ParseError::FixedHeaderReservedFlagsMismatch);
+    Ok(())
+}
+
+/// Decodes a variable-byte-int length prefix: up to 4 bytes, 7 bits each, continuation bit set on
+/// every byte but the last. Returns `Ok(None)` if `src` runs out before a terminating byte (one
+/// with the continuation bit clear) is seen, so the caller can tell "need more bytes" apart from
+/// `Err(InvalidLength)`, which means 4 bytes were seen and none of them terminated the encoding.
 pub fn decode_variable_length(src: &[u8]) -> Result<Option<(usize, usize)>, ParseError> {
-    if let Some((len, consumed, more)) = src
-        .iter()
-        .enumerate()
-        .scan((0, true), |state, (idx, x)| {
-            if !state.1 || idx > 3 {
-                return None;
-            }
-            state.0 += ((x & 0x7F) as usize) << (idx * 7);
-            state.1 = x & 0x80 != 0;
-            Some((state.0, idx + 1, state.1))
-        })
-        .last()
-    {
-        ensure!(!more || consumed < 4, ParseError::InvalidLength);
-        return Ok(Some((len, consumed)));
+    let mut value = 0usize;
+    for (idx, &byte) in src.iter().take(4).enumerate() {
+        value += ((byte & 0x7F) as usize) << (idx * 7);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, idx + 1)));
+        }
     }
 
+    ensure!(src.len() < 4, ParseError::InvalidLength);
     Ok(None)
 }
 
+/// Decodes the fixed header (packet type/flags byte plus the variable-byte remaining length)
+/// from the start of `src`, without copying or consuming anything. Returns `Ok(None)` if the
+/// header itself isn't fully buffered yet, and otherwise the header alongside the number of
+/// bytes it occupies, so the caller can tell how many bytes the whole frame needs without first
+/// having to materialize a `Bytes` out of a buffer that might not even hold a full packet yet.
+pub(crate) fn decode_header(src: &[u8]) -> Result<Option<(FixedHeader, usize)>, ParseError> {
+    if src.is_empty() {
+        return Ok(None);
+    }
+
+    let first = src[0];
+    match decode_variable_length(&src[1..])? {
+        Some((remaining_length, consumed)) => Ok(Some((
+            FixedHeader {
+                packet_type: first >> 4,
+                packet_flags: first & 0x0F,
+                remaining_length,
+            },
+            1 + consumed,
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Like [`decode_variable_length`], but reads directly off a cursor and advances it past the
+/// bytes consumed, for use inside decoders that have already moved on from the fixed header.
+fn read_variable_length(src: &mut Cursor<Bytes>) -> Result<usize, ParseError> {
+    match decode_variable_length(src.bytes())? {
+        Some((len, consumed)) => {
+            src.advance(consumed);
+            Ok(len)
+        }
+        None => Err(ParseError::InvalidLength),
+    }
+}
+
 fn decode_connect_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
@@ -99,24 +159,42 @@ fn decode_connect_packet(
         ParseError::FixedHeaderReservedFlagsMismatch
     );
 
-    ensure!(src.remaining() >= 10, ParseError::InvalidLength);
+    ensure!(src.remaining() >= 2, ParseError::InvalidLength);
     let len = src.get_u16_be();
     ensure!(
-        len == 4 && &src.bytes()[0..4] == b"MQTT",
+        src.remaining() >= len as usize,
         ParseError::InvalidProtocol
     );
-    src.advance(4);
+    // MQTT 3.1.1/5 clients send the 4-byte name "MQTT"; legacy 3.1 clients send the 6-byte
+    // "MQIsdp". Each protocol name only ever pairs with its own set of levels.
+    let level_range: &[u8] = match (len, src.bytes()) {
+        (4, name) if &name[0..4] == b"MQTT" => &[DEFAULT_MQTT_LEVEL, MQTT_LEVEL_5],
+        (6, name) if &name[0..6] == b"MQIsdp" => &[MQTT_LEVEL_3_1],
+        _ => return Err(ParseError::InvalidProtocol),
+    };
+    src.advance(len as usize);
 
+    ensure!(src.remaining() >= 4, ParseError::InvalidLength);
     let level = src.get_u8();
     ensure!(
-        level == DEFAULT_MQTT_LEVEL,
+        level_range.contains(&level),
         ParseError::UnsupportedProtocolLevel
     );
 
     let flags = src.get_u8();
     ensure!((flags & 0x01) == 0, ParseError::ConnectReservedFlagSet);
 
+    // The caller can't know the version before this packet is decoded (this is the packet that
+    // establishes it for the rest of the connection), so it's derived here from the level just
+    // read off the wire rather than trusted from a caller-supplied flag.
+    let is_v5 = level == MQTT_LEVEL_5;
+
     let keep_alive = src.get_u16_be();
+    let properties = if is_v5 {
+        v5::decode_properties(src)?
+    } else {
+        Vec::new()
+    };
     let client_id = decode_utf8_str(src)?;
 
     ensure!(
@@ -124,13 +202,19 @@ fn decode_connect_packet(
         ParseError::InvalidClientId
     );
 
-    let topic = if check_flag!(flags, ConnectFlags::WILL) {
-        Some(decode_utf8_str(src)?)
-    } else {
-        None
-    };
-    let message = if check_flag!(flags, ConnectFlags::WILL) {
-        Some(decode_length_bytes(src)?)
+    let last_will = if check_flag!(flags, ConnectFlags::WILL) {
+        let will_properties = if is_v5 {
+            v5::decode_properties(src)?
+        } else {
+            Vec::new()
+        };
+        Some(LastWill {
+            qos: QoS::from((flags & ConnectFlags::WILL_QOS.bits()) >> WILL_QOS_SHIFT),
+            retain: check_flag!(flags, ConnectFlags::WILL_RETAIN),
+            topic: decode_utf8_str(src)?,
+            message: decode_length_bytes(src)?,
+            properties: will_properties,
+        })
     } else {
         None
     };
@@ -144,16 +228,6 @@ fn decode_connect_packet(
     } else {
         None
     };
-    let last_will = if topic.is_some() {
-        Some(LastWill {
-            qos: QoS::from((flags & ConnectFlags::WILL_QOS.bits()) >> WILL_QOS_SHIFT),
-            retain: check_flag!(flags, ConnectFlags::WILL_RETAIN),
-            topic: topic.unwrap(),
-            message: message.unwrap(),
-        })
-    } else {
-        None
-    };
 
     Ok(Packet::Connect(Connect {
         protocol: Protocol::MQTT(level),
@@ -163,6 +237,7 @@ fn decode_connect_packet(
         last_will,
         username,
         password,
+        properties,
     }))
 }
 
@@ -192,6 +267,7 @@ fn decode_connect_ack_packet(
 fn decode_publish_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
+    version: ProtocolVersion,
 ) -> Result<Packet, ParseError> {
     let topic = decode_utf8_str(src)?;
     let qos = QoS::from((header.packet_flags & 0b0110) >> 1);
@@ -200,6 +276,11 @@ fn decode_publish_packet(
     } else {
         Some(read_u16(src)?)
     };
+    let properties = if version == ProtocolVersion::MQTT5 {
+        v5::decode_properties(src)?
+    } else {
+        Vec::new()
+    };
 
     let len = src.remaining();
     let payload = take(src, len);
@@ -210,6 +291,7 @@ fn decode_publish_packet(
         retain: (header.packet_flags & 0b0001) == 0b0001,
         topic,
         packet_id,
+        properties,
         payload,
     }))
 }
@@ -218,10 +300,7 @@ fn decode_publish_ack_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
 ) -> Result<Packet, ParseError> {
-    ensure!(
-        header.packet_flags.trailing_zeros() >= 4,
-        ParseError::FixedHeaderReservedFlagsMismatch
-    );
+    ensure_reserved_flags(header.packet_flags, 0b0000)?;
     Ok(Packet::PublishAck {
         packet_id: read_u16(src)?,
     })
@@ -231,11 +310,8 @@ fn decode_publish_rec_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
 ) -> Result<Packet, ParseError> {
-    ensure!(
-        header.packet_flags.trailing_zeros() >= 4,
-        ParseError::FixedHeaderReservedFlagsMismatch
-    );
-    Ok(Packet::PublishAck {
+    ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    Ok(Packet::PublishReceived {
         packet_id: read_u16(src)?,
     })
 }
@@ -244,10 +320,7 @@ fn decode_publish_rel_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
 ) -> Result<Packet, ParseError> {
-    ensure!(
-        header.packet_flags == 0b0010,
-        ParseError::FixedHeaderReservedFlagsMismatch
-    );
+    ensure_reserved_flags(header.packet_flags, 0b0010)?;
     Ok(Packet::PublishRelease {
         packet_id: read_u16(src)?,
     })
@@ -257,11 +330,8 @@ fn decode_publish_comp_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
 ) -> Result<Packet, ParseError> {
-    ensure!(
-        header.packet_flags.trailing_zeros() >= 4,
-        ParseError::FixedHeaderReservedFlagsMismatch
-    );
-    Ok(Packet::PublishRelease {
+    ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    Ok(Packet::PublishComplete {
         packet_id: read_u16(src)?,
     })
 }
@@ -269,12 +339,18 @@ fn decode_publish_comp_packet(
 fn decode_subscribe_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
+    version: ProtocolVersion,
 ) -> Result<Packet, ParseError> {
     ensure!(
         header.packet_flags == 0b0010,
         ParseError::FixedHeaderReservedFlagsMismatch
     );
     let packet_id = read_u16(src)?;
+    let properties = if version == ProtocolVersion::MQTT5 {
+        v5::decode_properties(src)?
+    } else {
+        Vec::new()
+    };
     let mut topic_filters = Vec::new();
     while src.remaining() > 0 {
         let topic = decode_utf8_str(src)?;
@@ -285,6 +361,7 @@ fn decode_subscribe_packet(
 
     Ok(Packet::Subscribe {
         packet_id,
+        properties,
         topic_filters,
     })
 }
@@ -314,18 +391,25 @@ fn decode_subscribe_ack_packet(
 fn decode_unsubscribe_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
+    version: ProtocolVersion,
 ) -> Result<Packet, ParseError> {
     ensure!(
         header.packet_flags == 0b0010,
         ParseError::FixedHeaderReservedFlagsMismatch
     );
     let packet_id = read_u16(src)?;
+    let properties = if version == ProtocolVersion::MQTT5 {
+        v5::decode_properties(src)?
+    } else {
+        Vec::new()
+    };
     let mut topic_filters = Vec::new();
     while src.remaining() > 0 {
         topic_filters.push(decode_utf8_str(src)?);
     }
     Ok(Packet::Unsubscribe {
         packet_id,
+        properties,
         topic_filters,
     })
 }
@@ -334,10 +418,7 @@ fn decode_unsubscribe_ack_packet(
     src: &mut Cursor<Bytes>,
     header: FixedHeader,
 ) -> Result<Packet, ParseError> {
-    ensure!(
-        header.packet_flags.trailing_zeros() >= 4,
-        ParseError::FixedHeaderReservedFlagsMismatch
-    );
+    ensure_reserved_flags(header.packet_flags, 0b0000)?;
     Ok(Packet::UnsubscribeAck {
         packet_id: read_u16(src)?
     })
@@ -366,271 +447,566 @@ fn read_u16(src: &mut Cursor<Bytes>) -> Result<u16, ParseError> {
     Ok(src.get_u16_be())
 }
 
+fn read_u8(src: &mut Cursor<Bytes>) -> Result<u8, ParseError> {
+    ensure!(src.remaining() >= 1, ParseError::InvalidLength);
+    Ok(src.get_u8())
+}
+
+fn read_u32(src: &mut Cursor<Bytes>) -> Result<u32, ParseError> {
+    ensure!(src.remaining() >= 4, ParseError::InvalidLength);
+    Ok(src.get_u32_be())
+}
+
 #[cfg(test)]
 mod tests {
+    use bytes::BytesMut;
+
     use super::*;
+    use crate::codec::encode::Encode;
+    use crate::codec::Codec;
+    use crate::error::EncodeError;
+
+    fn bytes_of(b: &[u8]) -> Bytes {
+        Bytes::from(b.to_vec())
+    }
+
+    fn str_prop(s: &str) -> String<Bytes> {
+        String::try_from(bytes_of(s.as_bytes())).unwrap()
+    }
+
+    /// Decodes one full packet out of `raw`, mirroring the header-then-body flow
+    /// [`super::super::version::Codec::decode`] drives in production.
+    fn decode_full_packet(raw: &[u8], version: ProtocolVersion) -> Result<Packet, ParseError> {
+        let (header, header_len) = decode_header(raw)?.expect("full header present in fixture");
+        let mut cursor = Cursor::new(bytes_of(raw));
+        cursor.advance(header_len);
+        Ok(read_packet(&mut cursor, header, version)?.expect("full packet present in fixture"))
+    }
 
     #[test]
     fn test_decode_variable_length() {
         macro_rules! assert_variable_length (
-            ($bytes:expr, $res:expr) => {{
-                assert_eq!(decode_variable_length($bytes), Ok(Some($res)));
-            }};
-
-            ($bytes:expr, $res:expr, $rest:expr) => {{
-                assert_eq!(decode_variable_length($bytes), Ok(Some($res)));
+            ($bytes:expr, $len:expr, $consumed:expr) => {{
+                assert_eq!(decode_variable_length($bytes), Ok(Some(($len, $consumed))));
             }};
         );
 
-        assert_variable_length!(b"\x7f\x7f", 127, b"\x7f");
+        // Trailing bytes beyond the encoding itself are left unconsumed.
+        assert_variable_length!(b"\x7f\x7f", 127, 1);
 
-        assert_eq!(decode_variable_length(b"\xff\xff\xff"), Ok(None));
+        assert_eq!(decode_variable_length(b""), Ok(None));
         assert_eq!(
             decode_variable_length(b"\xff\xff\xff\xff\xff\xff"),
             Err(ParseError::InvalidLength)
         );
 
-        assert_variable_length!(b"\x00", 0);
-        assert_variable_length!(b"\x7f", 127);
-        assert_variable_length!(b"\x80\x01", 128);
-        assert_variable_length!(b"\xff\x7f", 16383);
-        assert_variable_length!(b"\x80\x80\x01", 16384);
-        assert_variable_length!(b"\xff\xff\x7f", 2097151);
-        assert_variable_length!(b"\x80\x80\x80\x01", 2097152);
-        assert_variable_length!(b"\xff\xff\xff\x7f", 268435455);
-    }
-
-    // #[test]
-    // fn test_decode_header() {
-    //     assert_eq!(
-    //         decode_header(b"\x20\x7f"),
-    //         Done(
-    //             &b""[..],
-    //             FixedHeader {
-    //                 packet_type: CONNACK,
-    //                 packet_flags: 0,
-    //                 remaining_length: 127,
-    //             }
-    //         )
-    //     );
-
-    //     assert_eq!(
-    //         decode_header(b"\x3C\x82\x7f"),
-    //         Done(
-    //             &b""[..],
-    //             FixedHeader {
-    //                 packet_type: PUBLISH,
-    //                 packet_flags: 0x0C,
-    //                 remaining_length: 16258,
-    //             }
-    //         )
-    //     );
-
-    //     assert_eq!(decode_header(b"\x20"), Incomplete(Needed::Unknown));
-    // }
+        assert_variable_length!(b"\x00", 0, 1);
+        assert_variable_length!(b"\x7f", 127, 1);
+        assert_variable_length!(b"\x80\x01", 128, 2);
+        assert_variable_length!(b"\xff\x7f", 16383, 2);
+        assert_variable_length!(b"\x80\x80\x01", 16384, 3);
+        assert_variable_length!(b"\xff\xff\x7f", 2097151, 3);
+        assert_variable_length!(b"\x80\x80\x80\x01", 2097152, 4);
+        assert_variable_length!(b"\xff\xff\xff\x7f", 268435455, 4);
+    }
 
     #[test]
-    fn test_decode_connect_packets() {
+    fn test_decode_header() {
         assert_eq!(
-            decode_connect_packet(
-                b"\x00\x04MQTT\x04\xC0\x00\x3C\x00\x0512345\x00\x04user\x00\x04pass"
-            ),
-            Ok(Packet::Connect {
-                protocol: Protocol::MQTT(4),
+            decode_header(b"\x20\x7f"),
+            Ok(Some((
+                FixedHeader {
+                    packet_type: CONNACK,
+                    packet_flags: 0,
+                    remaining_length: 127,
+                },
+                2,
+            )))
+        );
+
+        assert_eq!(
+            decode_header(b"\x3C\x82\x7f"),
+            Ok(Some((
+                FixedHeader {
+                    packet_type: PUBLISH,
+                    packet_flags: 0x0C,
+                    remaining_length: 16258,
+                },
+                3,
+            )))
+        );
+
+        assert_eq!(decode_header(b""), Ok(None));
+        assert_eq!(decode_header(b"\x20"), Ok(None));
+
+        // Regression test: a remaining-length byte whose continuation bit is still set, with no
+        // further bytes buffered yet, must be reported as incomplete rather than as a (wrong)
+        // successfully-decoded value.
+        assert_eq!(decode_header(b"\xc0\x80"), Ok(None));
+    }
+
+    #[test]
+    fn test_decode_connect_packet_v311() {
+        let packet = decode_full_packet(
+            b"\x10\x1d\x00\x04MQTT\x04\xC0\x00\x3C\x00\x0512345\x00\x04user\x00\x04pass",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
+        assert_eq!(
+            packet,
+            Packet::Connect(Connect {
+                protocol: Protocol::MQTT(DEFAULT_MQTT_LEVEL),
                 clean_session: false,
                 keep_alive: 60,
-                client_id: "12345".to_owned(),
+                client_id: str_prop("12345"),
                 last_will: None,
-                username: Some("user".to_owned()),
-                password: Some(Bytes::from(&b"pass"[..])),
+                username: Some(str_prop("user")),
+                password: Some(bytes_of(b"pass")),
+                properties: Vec::new(),
             })
         );
 
+        let packet = decode_full_packet(
+            b"\x10\x21\x00\x04MQTT\x04\x14\x00\x3C\x00\x0512345\x00\x05topic\x00\x07message",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
         assert_eq!(
-            decode_connect_packet(
-                b"\x00\x04MQTT\x04\x14\x00\x3C\x00\x0512345\x00\x05topic\x00\x07message"
-            ),
-            Ok(Packet::Connect {
-                protocol: Protocol::MQTT(4),
+            packet,
+            Packet::Connect(Connect {
+                protocol: Protocol::MQTT(DEFAULT_MQTT_LEVEL),
                 clean_session: false,
                 keep_alive: 60,
-                client_id: "12345".to_owned(),
+                client_id: str_prop("12345"),
                 last_will: Some(LastWill {
                     qos: QoS::ExactlyOnce,
                     retain: false,
-                    topic: "topic".to_owned(),
-                    message: Bytes::from(&b"message"[..]),
+                    topic: str_prop("topic"),
+                    message: bytes_of(b"message"),
+                    properties: Vec::new(),
                 }),
                 username: None,
                 password: None,
+                properties: Vec::new(),
             })
         );
+    }
 
+    /// MQTT 3.1 predates "MQTT"/level 4: legacy clients send the 6-byte protocol name "MQIsdp"
+    /// paired with level 3.
+    #[test]
+    fn test_decode_connect_packet_v31() {
+        let packet = decode_full_packet(
+            b"\x10\x12\x00\x06MQIsdp\x03\x02\x00\x3C\x00\x04test",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
         assert_eq!(
-            decode_connect_packet(b"\x00\x02MQ"),
-            Err(ParseError::InvalidProtocol),
+            packet,
+            Packet::Connect(Connect {
+                protocol: Protocol::MQTT(MQTT_LEVEL_3_1),
+                clean_session: true,
+                keep_alive: 60,
+                client_id: str_prop("test"),
+                last_will: None,
+                username: None,
+                password: None,
+                properties: Vec::new(),
+            })
         );
+    }
+
+    #[test]
+    fn test_decode_connect_packet_errors() {
+        let header = FixedHeader {
+            packet_type: CONNECT,
+            packet_flags: 0,
+            remaining_length: 0,
+        };
+
+        let mut src = Cursor::new(bytes_of(b"\x00\x02MQ"));
         assert_eq!(
-            decode_connect_packet(b"\x00\x04MQAA"),
-            Err(ParseError::InvalidProtocol),
+            decode_connect_packet(&mut src, header),
+            Err(ParseError::InvalidProtocol)
         );
+
+        let mut src = Cursor::new(bytes_of(b"\x00\x04MQAA"));
         assert_eq!(
-            decode_connect_packet(b"\x00\x04MQTT\x03"),
-            Err(ParseError::UnsupportedProtocolLevel),
+            decode_connect_packet(&mut src, header),
+            Err(ParseError::InvalidProtocol)
         );
+
+        // "MQTT" only ever pairs with level 4 or 5, never the "MQIsdp" (3.1) level.
+        let mut src = Cursor::new(bytes_of(b"\x00\x04MQTT\x03\x00\x00\x00"));
         assert_eq!(
-            decode_connect_packet(b"\x00\x04MQTT\x04\xff"),
-            Err(ParseError::ConnectReservedFlagSet)
+            decode_connect_packet(&mut src, header),
+            Err(ParseError::UnsupportedProtocolLevel)
         );
 
+        // Likewise "MQIsdp" only ever pairs with level 3, never the "MQTT" (3.1.1/5) levels.
+        let mut src = Cursor::new(bytes_of(b"\x00\x06MQIsdp\x04\x00\x00\x00"));
         assert_eq!(
-            decode_connect_ack_packet(b"\x01\x04"),
-            (SESSION_PRESENT, ConnectCode::BadUserNameOrPassword)
+            decode_connect_packet(&mut src, header),
+            Err(ParseError::UnsupportedProtocolLevel)
         );
 
+        let mut src = Cursor::new(bytes_of(b"\x00\x04MQTT\x04\xff\x00\x00"));
         assert_eq!(
-            decode_connect_ack_packet(b"\x03\x04"),
-            Error(ErrorKind::Custom(RESERVED_FLAG))
+            decode_connect_packet(&mut src, header),
+            Err(ParseError::ConnectReservedFlagSet)
         );
+    }
 
+    /// Regression test for a v5 CONNECT whose Properties block wasn't being decoded at all: the
+    /// client identifier that follows it would otherwise be read starting mid-property-block.
+    #[test]
+    fn test_decode_connect_packet_v5_properties() {
+        let packet = decode_full_packet(
+            b"\x10\x15\x00\x04MQTT\x05\x02\x00\x00\x05\x11\x00\x00\x00\x0A\x00\x03abc",
+            ProtocolVersion::MQTT5,
+        )
+        .unwrap();
         assert_eq!(
-            decode_packet(b"\x20\x02\x01\x04"),
-            Done(
-                &b""[..],
-                Packet::ConnectAck {
-                    session_present: true,
-                    return_code: ConnectReturnCode::BadUserNameOrPassword,
-                }
-            )
+            packet,
+            Packet::Connect(Connect {
+                protocol: Protocol::MQTT(MQTT_LEVEL_5),
+                clean_session: true,
+                keep_alive: 0,
+                client_id: str_prop("abc"),
+                last_will: None,
+                username: None,
+                password: None,
+                properties: vec![Property::SessionExpiryInterval(10)],
+            })
         );
+    }
 
+    /// Regression test: `Codec::decode` can't know a connection's version before its first
+    /// CONNECT is decoded, so it always calls `read_packet` with the `MQTT311` placeholder for
+    /// that one packet. CONNECT must derive its own v5-ness from the protocol level on the wire
+    /// rather than trusting that placeholder, or a real v5 CONNECT's Properties block gets
+    /// misread as part of the client identifier.
+    #[test]
+    fn test_decode_connect_packet_v5_properties_via_placeholder_version() {
+        let packet = decode_full_packet(
+            b"\x10\x15\x00\x04MQTT\x05\x02\x00\x00\x05\x11\x00\x00\x00\x0A\x00\x03abc",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
         assert_eq!(
-            decode_packet(b"\xe0\x00"),
-            Done(&b""[..], Packet::Disconnect)
+            packet,
+            Packet::Connect(Connect {
+                protocol: Protocol::MQTT(MQTT_LEVEL_5),
+                clean_session: true,
+                keep_alive: 0,
+                client_id: str_prop("abc"),
+                last_will: None,
+                username: None,
+                password: None,
+                properties: vec![Property::SessionExpiryInterval(10)],
+            })
         );
     }
 
     #[test]
-    fn test_decode_publish_packets() {
+    fn test_decode_connect_ack_packet() {
+        let packet = decode_full_packet(b"\x20\x02\x01\x04", ProtocolVersion::MQTT311).unwrap();
         assert_eq!(
-            decode_publish_header(b"\x00\x05topic\x12\x34"),
-            Done(&b""[..], ("topic".to_owned(), 0x1234))
+            packet,
+            Packet::ConnectAck {
+                session_present: true,
+                return_code: ConnectCode::BadUserNameOrPassword,
+            }
         );
+    }
 
+    #[test]
+    fn test_decode_connect_ack_packet_v5_success_shortcut() {
+        let packet = decode_full_packet(b"\x20\x01\x00", ProtocolVersion::MQTT5).unwrap();
         assert_eq!(
-            decode_packet(b"\x3d\x0D\x00\x05topic\x43\x21data"),
-            Done(
-                &b""[..],
-                Packet::Publish {
-                    dup: true,
-                    retain: true,
-                    qos: QoS::ExactlyOnce,
-                    topic: "topic".to_owned(),
-                    packet_id: Some(0x4321),
-                    payload: PayloadPromise::from(&b"data"[..]),
-                }
-            )
+            packet,
+            Packet::ConnectAckV5 {
+                session_present: false,
+                reason_code: ConnAckReasonCode::Success,
+                properties: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_disconnect_packet() {
+        let packet = decode_full_packet(b"\xe0\x00", ProtocolVersion::MQTT311).unwrap();
+        assert_eq!(packet, Packet::Disconnect);
+    }
+
+    #[test]
+    fn test_decode_publish_packets_v311() {
+        let packet = decode_full_packet(
+            b"\x3d\x0D\x00\x05topic\x43\x21data",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
+        assert_eq!(
+            packet,
+            Packet::Publish(Publish {
+                dup: true,
+                retain: true,
+                qos: QoS::ExactlyOnce,
+                topic: str_prop("topic"),
+                packet_id: Some(0x4321),
+                properties: Vec::new(),
+                payload: bytes_of(b"data"),
+            })
         );
+
+        let packet =
+            decode_full_packet(b"\x30\x0b\x00\x05topicdata", ProtocolVersion::MQTT311).unwrap();
         assert_eq!(
-            decode_packet(b"\x30\x0b\x00\x05topicdata"),
-            Done(
-                &b""[..],
-                Packet::Publish {
-                    dup: false,
-                    retain: false,
-                    qos: QoS::AtMostOnce,
-                    topic: "topic".to_owned(),
-                    packet_id: None,
-                    payload: PayloadPromise::from(&b"data"[..]),
-                }
-            )
+            packet,
+            Packet::Publish(Publish {
+                dup: false,
+                retain: false,
+                qos: QoS::AtMostOnce,
+                topic: str_prop("topic"),
+                packet_id: None,
+                properties: Vec::new(),
+                payload: bytes_of(b"data"),
+            })
         );
 
+        for (bytes, packet) in [
+            (
+                &b"\x40\x02\x43\x21"[..],
+                Packet::PublishAck { packet_id: 0x4321 },
+            ),
+            (
+                &b"\x50\x02\x43\x21"[..],
+                Packet::PublishReceived { packet_id: 0x4321 },
+            ),
+            (
+                // PUBREL reserves flags `0b0010`, unlike the other acks here.
+                &b"\x62\x02\x43\x21"[..],
+                Packet::PublishRelease { packet_id: 0x4321 },
+            ),
+            (
+                &b"\x70\x02\x43\x21"[..],
+                Packet::PublishComplete { packet_id: 0x4321 },
+            ),
+        ] {
+            assert_eq!(
+                decode_full_packet(bytes, ProtocolVersion::MQTT311).unwrap(),
+                packet
+            );
+        }
+    }
+
+    /// Regression test for a v5 PUBLISH whose Properties block wasn't being decoded at all: the
+    /// payload that follows it would otherwise start with the block's length-prefix byte.
+    #[test]
+    fn test_decode_publish_packet_v5_properties() {
+        let packet =
+            decode_full_packet(b"\x32\x08\x00\x01t\x00\x07\x00hi", ProtocolVersion::MQTT5)
+                .unwrap();
         assert_eq!(
-            decode_packet(b"\x40\x02\x43\x21"),
-            Done(&b""[..], Packet::PublishAck { packet_id: 0x4321 })
+            packet,
+            Packet::Publish(Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic: str_prop("t"),
+                packet_id: Some(7),
+                properties: Vec::new(),
+                payload: bytes_of(b"hi"),
+            })
         );
+    }
+
+    #[test]
+    fn test_decode_subscribe_packets() {
+        let packet = decode_full_packet(
+            b"\x82\x12\x12\x34\x00\x04test\x01\x00\x06filter\x02",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
         assert_eq!(
-            decode_packet(b"\x50\x02\x43\x21"),
-            Done(&b""[..], Packet::PublishReceived { packet_id: 0x4321 })
+            packet,
+            Packet::Subscribe {
+                packet_id: 0x1234,
+                properties: Vec::new(),
+                topic_filters: vec![
+                    (str_prop("test"), QoS::AtLeastOnce),
+                    (str_prop("filter"), QoS::ExactlyOnce),
+                ],
+            }
         );
+
+        let packet = decode_full_packet(
+            b"\x90\x05\x12\x34\x01\x80\x02",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
         assert_eq!(
-            decode_packet(b"\x60\x02\x43\x21"),
-            Done(&b""[..], Packet::PublishRelease { packet_id: 0x4321 })
+            packet,
+            Packet::SubscribeAck {
+                packet_id: 0x1234,
+                status: vec![
+                    SubscribeReturnCode::Success(QoS::AtLeastOnce),
+                    SubscribeReturnCode::Failure,
+                    SubscribeReturnCode::Success(QoS::ExactlyOnce),
+                ],
+            }
         );
+
+        let packet = decode_full_packet(
+            b"\xa2\x10\x12\x34\x00\x04test\x00\x06filter",
+            ProtocolVersion::MQTT311,
+        )
+        .unwrap();
         assert_eq!(
-            decode_packet(b"\x70\x02\x43\x21"),
-            Done(&b""[..], Packet::PublishComplete { packet_id: 0x4321 })
+            packet,
+            Packet::Unsubscribe {
+                packet_id: 0x1234,
+                properties: Vec::new(),
+                topic_filters: vec![str_prop("test"), str_prop("filter")],
+            }
         );
+
+        let packet = decode_full_packet(b"\xb0\x02\x43\x21", ProtocolVersion::MQTT311).unwrap();
+        assert_eq!(packet, Packet::UnsubscribeAck { packet_id: 0x4321 });
     }
 
     #[test]
-    fn test_decode_subscribe_packets() {
-        let p = Packet::Subscribe {
-            packet_id: 0x1234,
-            topic_filters: vec![
-                ("test".to_owned(), QoS::AtLeastOnce),
-                ("filter".to_owned(), QoS::ExactlyOnce),
-            ],
-        };
-
+    fn test_decode_ping_packets() {
         assert_eq!(
-            decode_subscribe_header(b"\x12\x34\x00\x04test\x01\x00\x06filter\x02"),
-            Done(&b""[..], p.clone())
+            decode_full_packet(b"\xc0\x00", ProtocolVersion::MQTT311).unwrap(),
+            Packet::PingRequest
         );
         assert_eq!(
-            decode_packet(b"\x82\x12\x12\x34\x00\x04test\x01\x00\x06filter\x02"),
-            Done(&b""[..], p)
+            decode_full_packet(b"\xd0\x00", ProtocolVersion::MQTT311).unwrap(),
+            Packet::PingResponse
         );
+    }
 
-        let p = Packet::SubscribeAck {
-            packet_id: 0x1234,
-            status: vec![
-                SubscribeReturnCode::Success(QoS::AtLeastOnce),
-                SubscribeReturnCode::Failure,
-                SubscribeReturnCode::Success(QoS::ExactlyOnce),
-            ],
-        };
+    /// Regression test: a declared property length longer than the bytes actually available
+    /// used to panic (e.g. inside `get_u32_be`) instead of reporting a malformed packet.
+    #[test]
+    fn test_decode_properties_rejects_truncated_block() {
+        let mut src = Cursor::new(bytes_of(b"\x0A\x02"));
+        assert_eq!(
+            v5::decode_properties(&mut src),
+            Err(ParseError::MalformedProperty)
+        );
+    }
 
+    #[test]
+    fn test_decode_properties_rejects_duplicate_non_user_property() {
+        // Two SessionExpiryInterval (0x11) entries back to back: every identifier but
+        // UserProperty may appear at most once.
+        let mut src = Cursor::new(bytes_of(
+            b"\x0A\x11\x00\x00\x00\x0A\x11\x00\x00\x00\x0B",
+        ));
         assert_eq!(
-            decode_subscribe_ack_header(b"\x12\x34\x01\x80\x02"),
-            Done(&b""[..], p.clone())
+            v5::decode_properties(&mut src),
+            Err(ParseError::DuplicateProperty)
         );
+    }
 
+    #[test]
+    fn test_decode_properties_allows_duplicate_user_property() {
+        // UserProperty (0x26) is explicitly exempt from the duplicate check: it's the one
+        // property meant to be repeated.
+        let mut src = Cursor::new(bytes_of(
+            b"\x0E\x26\x00\x01a\x00\x01b\x26\x00\x01a\x00\x01b",
+        ));
         assert_eq!(
-            decode_packet(b"\x90\x05\x12\x34\x01\x80\x02"),
-            Done(&b""[..], p)
+            v5::decode_properties(&mut src),
+            Ok(vec![
+                Property::UserProperty(str_prop("a"), str_prop("b")),
+                Property::UserProperty(str_prop("a"), str_prop("b")),
+            ])
         );
+    }
 
-        let p = Packet::Unsubscribe {
-            packet_id: 0x1234,
-            topic_filters: vec!["test".to_owned(), "filter".to_owned()],
+    #[test]
+    fn test_decode_v5_puback_rejects_bad_reserved_flags() {
+        let header = FixedHeader {
+            packet_type: PUBACK,
+            packet_flags: 0b0001,
+            remaining_length: 0,
         };
-
+        let mut src = Cursor::new(bytes_of(b""));
         assert_eq!(
-            decode_unsubscribe_header(b"\x12\x34\x00\x04test\x00\x06filter"),
-            Done(&b""[..], p.clone())
+            v5::read_packet_v5(&mut src, header),
+            Err(ParseError::FixedHeaderReservedFlagsMismatch)
         );
+    }
+
+    /// Regression test: SUBACK and AUTH used to skip the reserved-flags check the v5
+    /// PUBACK/PUBREC/PUBREL/PUBCOMP/UNSUBACK/ping/disconnect decoders apply.
+    #[test]
+    fn test_decode_v5_suback_and_auth_reject_bad_reserved_flags() {
+        let suback_header = FixedHeader {
+            packet_type: SUBACK,
+            packet_flags: 0b0001,
+            remaining_length: 0,
+        };
+        let mut src = Cursor::new(bytes_of(b""));
         assert_eq!(
-            decode_packet(b"\xa2\x10\x12\x34\x00\x04test\x00\x06filter"),
-            Done(&b""[..], p)
+            v5::read_packet_v5(&mut src, suback_header),
+            Err(ParseError::FixedHeaderReservedFlagsMismatch)
         );
 
+        let auth_header = FixedHeader {
+            packet_type: AUTH,
+            packet_flags: 0b0001,
+            remaining_length: 0,
+        };
+        let mut src = Cursor::new(bytes_of(b""));
         assert_eq!(
-            decode_packet(b"\xb0\x02\x43\x21"),
-            Done(&b""[..], Packet::UnsubscribeAck { packet_id: 0x4321 })
+            v5::read_packet_v5(&mut src, auth_header),
+            Err(ParseError::FixedHeaderReservedFlagsMismatch)
         );
     }
 
     #[test]
-    fn test_decode_ping_packets() {
-        assert_eq!(
-            decode_packet(b"\xc0\x00"),
-            Done(&b""[..], Packet::PingRequest)
-        );
+    fn test_codec_decode_incremental() {
+        let frame = b"\x10\x1d\x00\x04MQTT\x04\xC0\x00\x3C\x00\x0512345\x00\x04user\x00\x04pass";
+        let mut buf = BytesMut::from(&frame[..frame.len() - 1]);
+        let mut codec = Codec::new();
+
+        assert_eq!(codec.decode(&mut buf), Ok(None));
+
+        buf.extend_from_slice(&frame[frame.len() - 1..]);
+        let packet = codec.decode(&mut buf).unwrap().expect("full packet");
+        assert!(matches!(packet, Packet::Connect(_)));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_encode_decode_publish_roundtrip() {
+        let packet = Packet::Publish(Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: str_prop("topic"),
+            packet_id: Some(42),
+            properties: Vec::new(),
+            payload: Bytes::from_static(b"hello"),
+        });
+
+        let mut buf = BytesMut::new();
+        packet.encode(&mut buf, u32::MAX).unwrap();
+
+        let decoded = decode_full_packet(&buf, ProtocolVersion::MQTT311).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn test_encode_over_max_packet_size() {
+        let mut buf = BytesMut::new();
         assert_eq!(
-            decode_packet(b"\xd0\x00"),
-            Done(&b""[..], Packet::PingResponse)
+            Packet::PingRequest.encode(&mut buf, 1),
+            Err(EncodeError::OverMaxPacketSize)
         );
     }
 }
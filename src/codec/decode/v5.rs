@@ -0,0 +1,251 @@
+//! MQTT 5 decode path: property blocks, reason codes and the AUTH packet.
+//!
+//! CONNECT, PUBLISH, SUBSCRIBE and UNSUBSCRIBE are structurally the same across 3.1.1 and 5
+//! (modulo the property block, which those decoders read with [`decode_properties`] on the v5
+//! path) so they're routed through the existing decoders; everything that gained a reason code
+//! in v5 gets its own decode function below.
+
+use std::collections::HashSet;
+use std::io::Cursor;
+
+use bytes::{Buf, Bytes};
+
+use crate::error::ParseError;
+use crate::packet::*;
+use crate::proto::*;
+
+use super::{FixedHeader, decode_publish_packet, decode_subscribe_packet, decode_unsubscribe_packet};
+
+pub(super) fn read_packet_v5(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    match header.packet_type {
+        CONNECT => super::decode_connect_packet(src, header),
+        CONNACK => decode_connect_ack_packet(src),
+        PUBLISH => decode_publish_packet(src, header, ProtocolVersion::MQTT5),
+        PUBACK => decode_publish_ack_packet(src, header),
+        PUBREC => decode_publish_rec_packet(src, header),
+        PUBREL => decode_publish_rel_packet(src, header),
+        PUBCOMP => decode_publish_comp_packet(src, header),
+        SUBSCRIBE => decode_subscribe_packet(src, header, ProtocolVersion::MQTT5),
+        SUBACK => decode_subscribe_ack_packet(src, header),
+        UNSUBSCRIBE => decode_unsubscribe_packet(src, header, ProtocolVersion::MQTT5),
+        UNSUBACK => decode_unsubscribe_ack_packet(src, header),
+        PINGREQ => {
+            super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+            Ok(Packet::PingRequest)
+        }
+        PINGRESP => {
+            super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+            Ok(Packet::PingResponse)
+        }
+        DISCONNECT => decode_disconnect_packet(src, header),
+        AUTH => decode_auth_packet(src, header),
+        _ => Err(ParseError::UnsupportedPacketType),
+    }
+}
+
+/// Reads the variable-byte-int "property length" followed by that many bytes of properties.
+///
+/// Every identifier but `UserProperty` (0x26) may appear at most once; a repeat is rejected
+/// with `ParseError::DuplicateProperty`.
+pub(crate) fn decode_properties(src: &mut Cursor<Bytes>) -> Result<Vec<Property>, ParseError> {
+    let len = super::read_variable_length(src)?;
+    ensure!(src.remaining() >= len, ParseError::MalformedProperty);
+    let end = src.position() + len as u64;
+
+    let mut properties = Vec::new();
+    let mut seen = HashSet::new();
+
+    while src.position() < end {
+        let id = super::read_u8(src)?;
+        if id != 0x26 {
+            ensure!(seen.insert(id), ParseError::DuplicateProperty);
+        }
+
+        let property = match id {
+            0x01 => Property::PayloadFormatIndicator(super::read_u8(src)?),
+            0x02 => Property::MessageExpiryInterval(super::read_u32(src)?),
+            0x03 => Property::ContentType(super::decode_utf8_str(src)?),
+            0x08 => Property::ResponseTopic(super::decode_utf8_str(src)?),
+            0x09 => Property::CorrelationData(super::decode_length_bytes(src)?),
+            0x0B => Property::SubscriptionIdentifier(super::read_variable_length(src)?),
+            0x11 => Property::SessionExpiryInterval(super::read_u32(src)?),
+            0x12 => Property::AssignedClientIdentifier(super::decode_utf8_str(src)?),
+            0x13 => Property::ServerKeepAlive(super::read_u16(src)?),
+            0x15 => Property::AuthenticationMethod(super::decode_utf8_str(src)?),
+            0x16 => Property::AuthenticationData(super::decode_length_bytes(src)?),
+            0x17 => Property::RequestProblemInformation(super::read_u8(src)?),
+            0x18 => Property::WillDelayInterval(super::read_u32(src)?),
+            0x19 => Property::RequestResponseInformation(super::read_u8(src)?),
+            0x1A => Property::ResponseInformation(super::decode_utf8_str(src)?),
+            0x1C => Property::ServerReference(super::decode_utf8_str(src)?),
+            0x1F => Property::ReasonString(super::decode_utf8_str(src)?),
+            0x21 => Property::ReceiveMaximum(super::read_u16(src)?),
+            0x22 => Property::TopicAliasMaximum(super::read_u16(src)?),
+            0x23 => Property::TopicAlias(super::read_u16(src)?),
+            0x24 => Property::MaximumQoS(super::read_u8(src)?),
+            0x25 => Property::RetainAvailable(super::read_u8(src)?),
+            0x26 => {
+                let name = super::decode_utf8_str(src)?;
+                let value = super::decode_utf8_str(src)?;
+                Property::UserProperty(name, value)
+            }
+            0x27 => Property::MaximumPacketSize(super::read_u32(src)?),
+            0x28 => Property::WildcardSubscriptionAvailable(super::read_u8(src)?),
+            0x29 => Property::SubscriptionIdentifierAvailable(super::read_u8(src)?),
+            0x2A => Property::SharedSubscriptionAvailable(super::read_u8(src)?),
+            _ => return Err(ParseError::MalformedProperty),
+        };
+        properties.push(property);
+    }
+
+    ensure!(src.position() == end, ParseError::MalformedProperty);
+    Ok(properties)
+}
+
+/// Reads the reason code and property block shared by most v5 acks. Both are omitted when the
+/// remaining length is zero, which v5 defines to mean "success, no properties".
+fn decode_reason_and_properties(
+    src: &mut Cursor<Bytes>,
+) -> Result<(u8, Vec<Property>), ParseError> {
+    if src.remaining() == 0 {
+        return Ok((0x00, Vec::new()));
+    }
+
+    let reason = src.get_u8();
+    let properties = if src.remaining() == 0 {
+        Vec::new()
+    } else {
+        decode_properties(src)?
+    };
+    Ok((reason, properties))
+}
+
+fn decode_connect_ack_packet(src: &mut Cursor<Bytes>) -> Result<Packet, ParseError> {
+    ensure!(src.remaining() >= 1, ParseError::InvalidLength);
+    let flags = src.get_u8();
+    ensure!(
+        (flags & 0b1111_1110) == 0,
+        ParseError::ConnAckReservedFlagSet
+    );
+    let (reason, properties) = decode_reason_and_properties(src)?;
+
+    Ok(Packet::ConnectAckV5 {
+        session_present: (flags & 0b1) == 0b1,
+        reason_code: ConnAckReasonCode::from(reason),
+        properties,
+    })
+}
+
+fn decode_publish_ack_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let packet_id = super::read_u16(src)?;
+    let (reason, properties) = decode_reason_and_properties(src)?;
+    Ok(Packet::PublishAckV5 {
+        packet_id,
+        reason_code: PubAckReasonCode::from(reason),
+        properties,
+    })
+}
+
+fn decode_publish_rec_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let packet_id = super::read_u16(src)?;
+    let (reason, properties) = decode_reason_and_properties(src)?;
+    Ok(Packet::PublishReceivedV5 {
+        packet_id,
+        reason_code: PubAckReasonCode::from(reason),
+        properties,
+    })
+}
+
+fn decode_publish_rel_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0010)?;
+    let packet_id = super::read_u16(src)?;
+    let (reason, properties) = decode_reason_and_properties(src)?;
+    Ok(Packet::PublishReleaseV5 {
+        packet_id,
+        reason_code: PubRelReasonCode::from(reason),
+        properties,
+    })
+}
+
+fn decode_publish_comp_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let packet_id = super::read_u16(src)?;
+    let (reason, properties) = decode_reason_and_properties(src)?;
+    Ok(Packet::PublishCompleteV5 {
+        packet_id,
+        reason_code: PubRelReasonCode::from(reason),
+        properties,
+    })
+}
+
+fn decode_subscribe_ack_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let packet_id = super::read_u16(src)?;
+    let properties = decode_properties(src)?;
+    let reason_codes = src.bytes().iter().map(|&b| SubAckReasonCode::from(b)).collect();
+    let remaining = src.remaining();
+    src.advance(remaining);
+    Ok(Packet::SubscribeAckV5 {
+        packet_id,
+        reason_codes,
+        properties,
+    })
+}
+
+fn decode_unsubscribe_ack_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let packet_id = super::read_u16(src)?;
+    let properties = decode_properties(src)?;
+    let reason_codes = src.bytes().iter().map(|&b| UnsubAckReasonCode::from(b)).collect();
+    let remaining = src.remaining();
+    src.advance(remaining);
+    Ok(Packet::UnsubscribeAckV5 {
+        packet_id,
+        reason_codes,
+        properties,
+    })
+}
+
+fn decode_disconnect_packet(
+    src: &mut Cursor<Bytes>,
+    header: FixedHeader,
+) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let (reason, properties) = decode_reason_and_properties(src)?;
+    Ok(Packet::DisconnectV5 {
+        reason_code: DisconnectReasonCode::from(reason),
+        properties,
+    })
+}
+
+fn decode_auth_packet(src: &mut Cursor<Bytes>, header: FixedHeader) -> Result<Packet, ParseError> {
+    super::ensure_reserved_flags(header.packet_flags, 0b0000)?;
+    let (reason, properties) = decode_reason_and_properties(src)?;
+    Ok(Packet::Auth {
+        reason_code: AuthReasonCode::from(reason),
+        properties,
+    })
+}
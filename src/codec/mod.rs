@@ -0,0 +1,37 @@
+//! Wire-format details shared by the decoder and the encoder.
+
+use bitflags::bitflags;
+
+pub mod decode;
+pub mod encode;
+mod version;
+
+pub use version::Codec;
+
+/// Shift applied to the two WILL QoS bits in the CONNECT flags byte.
+pub const WILL_QOS_SHIFT: u8 = 3;
+
+bitflags! {
+    pub struct ConnectFlags: u8 {
+        const USERNAME      = 0b1000_0000;
+        const PASSWORD      = 0b0100_0000;
+        const WILL_RETAIN   = 0b0010_0000;
+        const WILL_QOS      = 0b0001_1000;
+        const WILL          = 0b0000_0100;
+        const CLEAN_SESSION = 0b0000_0010;
+    }
+}
+
+bitflags! {
+    pub struct ConnectAckFlags: u8 {
+        const SESSION_PRESENT = 0b0000_0001;
+    }
+}
+
+/// Parsed MQTT fixed header: packet type/flags nibble plus the decoded remaining length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedHeader {
+    pub packet_type: u8,
+    pub packet_flags: u8,
+    pub remaining_length: usize,
+}
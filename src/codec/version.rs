@@ -0,0 +1,111 @@
+//! Version-tagged decode facade: learns the protocol version from a connection's CONNECT packet
+//! and dispatches every packet after it to the right decode path, so callers embedding this
+//! crate (e.g. a broker) don't have to track the version themselves.
+
+use std::io::Cursor;
+
+use bytes::{Buf, BytesMut};
+
+use crate::error::ParseError;
+use crate::packet::Packet;
+use crate::proto::{Protocol, ProtocolVersion, CONNECT, MQTT_LEVEL_5};
+
+use super::decode::{decode_header, read_packet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// No CONNECT decoded yet; the only packet type that's legal here.
+    AwaitingConnect,
+    /// Version learned from the first CONNECT; every later CONNECT is rejected.
+    Latched(ProtocolVersion),
+}
+
+/// A per-connection decoder that multiplexes the 3.1.1 and 5 decode paths.
+///
+/// Append bytes to the caller's buffer as they arrive and pass it to [`Codec::decode`]; the
+/// `Codec` itself holds no bytes, only the connection's version state. `decode` returns
+/// `Ok(None)`, leaving the buffer untouched, until a full packet is available, mirroring
+/// [`read_packet`]'s incremental contract.
+#[derive(Debug, Clone, Copy)]
+pub struct Codec {
+    state: State,
+}
+
+impl Codec {
+    pub fn new() -> Codec {
+        Codec {
+            state: State::AwaitingConnect,
+        }
+    }
+
+    /// Decodes the next packet out of `src`, consuming its bytes on success.
+    pub fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, ParseError> {
+        // Peeked against a borrowed slice, not a cloned `Bytes`: on a connection fed bytes a few
+        // at a time, cloning the whole (growing) buffer on every call that doesn't yet have a
+        // full frame would be O(n^2) over the life of one packet.
+        let (header, header_len) = match decode_header(&src[..])? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+
+        match self.state {
+            State::AwaitingConnect if header.packet_type != CONNECT => {
+                return Err(ParseError::UnsupportedPacketType);
+            }
+            State::Latched(_) if header.packet_type == CONNECT => {
+                return Err(ParseError::UnsupportedPacketType);
+            }
+            _ => {}
+        }
+
+        if src.len() < header_len + header.remaining_length {
+            return Ok(None);
+        }
+
+        // The version only matters for packets decoded after the CONNECT; CONNECT itself
+        // decodes identically regardless, so any placeholder works while it's in flight.
+        let version = match self.state {
+            State::AwaitingConnect => ProtocolVersion::MQTT311,
+            State::Latched(version) => version,
+        };
+
+        // Only now, with a complete frame confirmed present, is a `Bytes` materialized: this is
+        // an O(1) refcount bump (`split_to` + `freeze`), not a copy of the buffered data.
+        let frame = src.split_to(header_len + header.remaining_length).freeze();
+        let mut cursor = Cursor::new(frame);
+        cursor.advance(header_len);
+
+        let packet = match read_packet(&mut cursor, header, version)? {
+            Some(packet) => packet,
+            None => return Ok(None),
+        };
+
+        if self.state == State::AwaitingConnect {
+            self.state = State::Latched(negotiated_version(&packet));
+        }
+
+        Ok(Some(packet))
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Codec {
+        Codec::new()
+    }
+}
+
+fn negotiated_version(packet: &Packet) -> ProtocolVersion {
+    match packet {
+        Packet::Connect(connect) => {
+            let Protocol::MQTT(level) = connect.protocol;
+            if level == MQTT_LEVEL_5 {
+                ProtocolVersion::MQTT5
+            } else {
+                ProtocolVersion::MQTT311
+            }
+        }
+        // Unreachable: `decode` only reaches here for the first packet on a connection, which
+        // the state-machine check above already requires to be CONNECT.
+        _ => ProtocolVersion::MQTT311,
+    }
+}
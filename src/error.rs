@@ -0,0 +1,33 @@
+//! Errors produced while parsing MQTT frames.
+
+use std::str::Utf8Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidProtocol,
+    UnsupportedProtocolLevel,
+    ConnectReservedFlagSet,
+    ConnAckReservedFlagSet,
+    InvalidClientId,
+    UnsupportedPacketType,
+    FixedHeaderReservedFlagsMismatch,
+    InvalidLength,
+    MalformedUtf8,
+    DuplicateProperty,
+    MalformedProperty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The packet's encoded size exceeds the negotiated maximum packet size.
+    OverMaxPacketSize,
+    /// The packet's remaining length doesn't fit in the 1-4 byte variable length encoding
+    /// (`> 268_435_455`).
+    RemainingLengthTooLarge,
+}
+
+impl From<Utf8Error> for ParseError {
+    fn from(_: Utf8Error) -> ParseError {
+        ParseError::MalformedUtf8
+    }
+}
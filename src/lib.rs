@@ -0,0 +1,13 @@
+//! Codec for encoding and decoding MQTT 3.1, 3.1.1 and 5 frames.
+
+mod codec;
+mod error;
+mod packet;
+mod proto;
+
+pub use crate::codec::decode::decode_variable_length;
+pub use crate::codec::encode::{write_variable_length, Encode};
+pub use crate::codec::Codec;
+pub use crate::error::{EncodeError, ParseError};
+pub use crate::packet::*;
+pub use crate::proto::*;
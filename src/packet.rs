@@ -0,0 +1,347 @@
+//! In-memory representation of decoded/to-be-encoded MQTT packets.
+
+use bytes::Bytes;
+use string::String;
+
+use crate::proto::{Protocol, QoS};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastWill {
+    pub qos: QoS,
+    pub retain: bool,
+    pub topic: String<Bytes>,
+    pub message: Bytes,
+    /// The v5 Will Properties block; always empty on 3.1/3.1.1 connections.
+    pub properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connect {
+    pub protocol: Protocol,
+    pub clean_session: bool,
+    pub keep_alive: u16,
+    pub client_id: String<Bytes>,
+    pub last_will: Option<LastWill>,
+    pub username: Option<String<Bytes>>,
+    pub password: Option<Bytes>,
+    /// The v5 Properties block; always empty on 3.1/3.1.1 connections.
+    pub properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: QoS,
+    pub retain: bool,
+    pub topic: String<Bytes>,
+    pub packet_id: Option<u16>,
+    /// The v5 Properties block; always empty on 3.1/3.1.1 connections.
+    pub properties: Vec<Property>,
+    pub payload: Bytes,
+}
+
+/// CONNACK return code, MQTT 3.1.1 `[MQTT-3.2.2-1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectCode {
+    ConnectionAccepted,
+    UnacceptableProtocolVersion,
+    BadClientId,
+    ServiceUnavailable,
+    BadUserNameOrPassword,
+    NotAuthorized,
+}
+
+impl From<u8> for ConnectCode {
+    fn from(v: u8) -> ConnectCode {
+        match v {
+            0 => ConnectCode::ConnectionAccepted,
+            1 => ConnectCode::UnacceptableProtocolVersion,
+            2 => ConnectCode::BadClientId,
+            3 => ConnectCode::ServiceUnavailable,
+            4 => ConnectCode::BadUserNameOrPassword,
+            5 => ConnectCode::NotAuthorized,
+            _ => ConnectCode::ServiceUnavailable,
+        }
+    }
+}
+
+impl From<ConnectCode> for u8 {
+    fn from(code: ConnectCode) -> u8 {
+        match code {
+            ConnectCode::ConnectionAccepted => 0,
+            ConnectCode::UnacceptableProtocolVersion => 1,
+            ConnectCode::BadClientId => 2,
+            ConnectCode::ServiceUnavailable => 3,
+            ConnectCode::BadUserNameOrPassword => 4,
+            ConnectCode::NotAuthorized => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscribeReturnCode {
+    Success(QoS),
+    Failure,
+}
+
+impl From<SubscribeReturnCode> for u8 {
+    fn from(code: SubscribeReturnCode) -> u8 {
+        match code {
+            SubscribeReturnCode::Success(QoS::AtMostOnce) => 0,
+            SubscribeReturnCode::Success(QoS::AtLeastOnce) => 1,
+            SubscribeReturnCode::Success(QoS::ExactlyOnce) => 2,
+            SubscribeReturnCode::Failure => 0x80,
+        }
+    }
+}
+
+/// A single MQTT 5 property, as carried in the property block of most v5 packets.
+///
+/// User properties (`UserProperty`) are the only kind allowed to repeat; every other variant
+/// may appear at most once per property block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(String<Bytes>),
+    ResponseTopic(String<Bytes>),
+    CorrelationData(Bytes),
+    SubscriptionIdentifier(usize),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(String<Bytes>),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(String<Bytes>),
+    AuthenticationData(Bytes),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(String<Bytes>),
+    ServerReference(String<Bytes>),
+    ReasonString(String<Bytes>),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQoS(u8),
+    RetainAvailable(u8),
+    UserProperty(String<Bytes>, String<Bytes>),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+macro_rules! reason_code {
+    ($name:ident { $($variant:ident = $value:expr,)+ }) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($variant,)+
+        }
+
+        impl From<u8> for $name {
+            fn from(v: u8) -> $name {
+                match v {
+                    $($value => $name::$variant,)+
+                    _ => $name::UnspecifiedError,
+                }
+            }
+        }
+
+        impl From<$name> for u8 {
+            fn from(code: $name) -> u8 {
+                match code {
+                    $($name::$variant => $value,)+
+                }
+            }
+        }
+    };
+}
+
+reason_code!(ConnAckReasonCode {
+    Success = 0x00,
+    UnspecifiedError = 0x80,
+    MalformedPacket = 0x81,
+    ProtocolError = 0x82,
+    ImplementationSpecificError = 0x83,
+    UnsupportedProtocolVersion = 0x84,
+    ClientIdentifierNotValid = 0x85,
+    BadUserNameOrPassword = 0x86,
+    NotAuthorized = 0x87,
+    ServerUnavailable = 0x88,
+    ServerBusy = 0x89,
+    Banned = 0x8A,
+    BadAuthenticationMethod = 0x8C,
+    TopicNameInvalid = 0x90,
+    PacketTooLarge = 0x95,
+    QuotaExceeded = 0x97,
+    PayloadFormatInvalid = 0x99,
+    RetainNotSupported = 0x9A,
+    QoSNotSupported = 0x9B,
+    UseAnotherServer = 0x9C,
+    ServerMoved = 0x9D,
+    ConnectionRateExceeded = 0x9F,
+});
+
+reason_code!(PubAckReasonCode {
+    Success = 0x00,
+    NoMatchingSubscribers = 0x10,
+    UnspecifiedError = 0x80,
+    ImplementationSpecificError = 0x83,
+    NotAuthorized = 0x87,
+    TopicNameInvalid = 0x90,
+    PacketIdentifierInUse = 0x91,
+    QuotaExceeded = 0x97,
+    PayloadFormatInvalid = 0x99,
+});
+
+reason_code!(PubRelReasonCode {
+    Success = 0x00,
+    UnspecifiedError = 0x80,
+    PacketIdentifierNotFound = 0x92,
+});
+
+reason_code!(SubAckReasonCode {
+    GrantedQoS0 = 0x00,
+    GrantedQoS1 = 0x01,
+    GrantedQoS2 = 0x02,
+    UnspecifiedError = 0x80,
+    ImplementationSpecificError = 0x83,
+    NotAuthorized = 0x87,
+    TopicFilterInvalid = 0x8F,
+    PacketIdentifierInUse = 0x91,
+    QuotaExceeded = 0x97,
+    SharedSubscriptionsNotSupported = 0x9E,
+    SubscriptionIdentifiersNotSupported = 0xA1,
+    WildcardSubscriptionsNotSupported = 0xA2,
+});
+
+reason_code!(UnsubAckReasonCode {
+    Success = 0x00,
+    NoSubscriptionExisted = 0x11,
+    UnspecifiedError = 0x80,
+    ImplementationSpecificError = 0x83,
+    NotAuthorized = 0x87,
+    TopicFilterInvalid = 0x8F,
+    PacketIdentifierInUse = 0x91,
+});
+
+reason_code!(DisconnectReasonCode {
+    NormalDisconnection = 0x00,
+    DisconnectWithWillMessage = 0x04,
+    UnspecifiedError = 0x80,
+    MalformedPacket = 0x81,
+    ProtocolError = 0x82,
+    ImplementationSpecificError = 0x83,
+    NotAuthorized = 0x87,
+    ServerBusy = 0x89,
+    ServerShuttingDown = 0x8B,
+    KeepAliveTimeout = 0x8D,
+    SessionTakenOver = 0x8E,
+    TopicFilterInvalid = 0x8F,
+    TopicNameInvalid = 0x90,
+    ReceiveMaximumExceeded = 0x93,
+    TopicAliasInvalid = 0x94,
+    PacketTooLarge = 0x95,
+    MessageRateTooHigh = 0x96,
+    QuotaExceeded = 0x97,
+    AdministrativeAction = 0x98,
+    PayloadFormatInvalid = 0x99,
+});
+
+reason_code!(AuthReasonCode {
+    Success = 0x00,
+    ContinueAuthentication = 0x18,
+    ReAuthenticate = 0x19,
+    UnspecifiedError = 0x80,
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Connect(Connect),
+    ConnectAck {
+        session_present: bool,
+        return_code: ConnectCode,
+    },
+    Publish(Publish),
+    PublishAck {
+        packet_id: u16,
+    },
+    PublishReceived {
+        packet_id: u16,
+    },
+    PublishRelease {
+        packet_id: u16,
+    },
+    PublishComplete {
+        packet_id: u16,
+    },
+    Subscribe {
+        packet_id: u16,
+        /// The v5 Properties block; always empty on 3.1/3.1.1 connections.
+        properties: Vec<Property>,
+        topic_filters: Vec<(String<Bytes>, QoS)>,
+    },
+    SubscribeAck {
+        packet_id: u16,
+        status: Vec<SubscribeReturnCode>,
+    },
+    Unsubscribe {
+        packet_id: u16,
+        /// The v5 Properties block; always empty on 3.1/3.1.1 connections.
+        properties: Vec<Property>,
+        topic_filters: Vec<String<Bytes>>,
+    },
+    UnsubscribeAck {
+        packet_id: u16,
+    },
+    PingRequest,
+    PingResponse,
+    Disconnect,
+
+    // MQTT 5 packets. These carry a reason code and an optional property block instead of the
+    // single-byte codes above; kept as their own variants for now rather than recast onto the
+    // 3.1.1 shapes above, since the two don't agree on what "success" looks like on the wire
+    // (an all-zero remaining length, vs. an explicit reason byte).
+    ConnectAckV5 {
+        session_present: bool,
+        reason_code: ConnAckReasonCode,
+        properties: Vec<Property>,
+    },
+    PublishAckV5 {
+        packet_id: u16,
+        reason_code: PubAckReasonCode,
+        properties: Vec<Property>,
+    },
+    PublishReceivedV5 {
+        packet_id: u16,
+        reason_code: PubAckReasonCode,
+        properties: Vec<Property>,
+    },
+    PublishReleaseV5 {
+        packet_id: u16,
+        reason_code: PubRelReasonCode,
+        properties: Vec<Property>,
+    },
+    PublishCompleteV5 {
+        packet_id: u16,
+        reason_code: PubRelReasonCode,
+        properties: Vec<Property>,
+    },
+    SubscribeAckV5 {
+        packet_id: u16,
+        reason_codes: Vec<SubAckReasonCode>,
+        properties: Vec<Property>,
+    },
+    UnsubscribeAckV5 {
+        packet_id: u16,
+        reason_codes: Vec<UnsubAckReasonCode>,
+        properties: Vec<Property>,
+    },
+    DisconnectV5 {
+        reason_code: DisconnectReasonCode,
+        properties: Vec<Property>,
+    },
+    Auth {
+        reason_code: AuthReasonCode,
+        properties: Vec<Property>,
+    },
+}
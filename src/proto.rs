@@ -0,0 +1,73 @@
+//! Protocol-level constants shared by the encoder and decoder.
+
+/// MQTT control packet types, as carried in the upper nibble of the fixed header.
+pub const CONNECT: u8 = 1;
+pub const CONNACK: u8 = 2;
+pub const PUBLISH: u8 = 3;
+pub const PUBACK: u8 = 4;
+pub const PUBREC: u8 = 5;
+pub const PUBREL: u8 = 6;
+pub const PUBCOMP: u8 = 7;
+pub const SUBSCRIBE: u8 = 8;
+pub const SUBACK: u8 = 9;
+pub const UNSUBSCRIBE: u8 = 10;
+pub const UNSUBACK: u8 = 11;
+pub const PINGREQ: u8 = 12;
+pub const PINGRESP: u8 = 13;
+pub const DISCONNECT: u8 = 14;
+pub const AUTH: u8 = 15;
+
+/// The default, and for a long time only, protocol level this crate decoded: MQTT 3.1.1.
+pub const DEFAULT_MQTT_LEVEL: u8 = 4;
+
+/// MQTT 3.1's protocol level, as carried by legacy `"MQIsdp"` CONNECT packets.
+pub const MQTT_LEVEL_3_1: u8 = 3;
+
+/// MQTT 5's protocol level, as carried by CONNECT packets that speak the current spec.
+pub const MQTT_LEVEL_5: u8 = 5;
+
+/// Which packet family `read_packet` should decode against. Learned from a CONNECT packet's
+/// protocol level and threaded through every subsequent call on the same connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// MQTT 3.1 or 3.1.1.
+    MQTT311,
+    /// MQTT 5.
+    MQTT5,
+}
+
+/// Quality of service, encoded in two bits of the fixed header (PUBLISH) or a payload byte
+/// (SUBSCRIBE/SUBACK).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+impl From<u8> for QoS {
+    fn from(v: u8) -> QoS {
+        match v {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            _ => QoS::ExactlyOnce,
+        }
+    }
+}
+
+impl From<QoS> for u8 {
+    fn from(qos: QoS) -> u8 {
+        match qos {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => 1,
+            QoS::ExactlyOnce => 2,
+        }
+    }
+}
+
+/// The protocol name and level negotiated by a CONNECT packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    MQTT(u8),
+}